@@ -1,15 +1,65 @@
-use std::fs;
-use std::path::Path;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use sysinfo::Disks;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+/// How often `ScanContext::maybe_emit` is allowed to push a progress event,
+/// so a fast scan over millions of tiny files doesn't flood the frontend.
+const PROGRESS_THROTTLE: Duration = Duration::from_millis(100);
+
+/// How often `ScanCache::save` is allowed to actually serialize and write the
+/// cache to disk. The frontend drives whole-drive scans by calling
+/// `get_folder_size` concurrently once per folder, so without this throttle
+/// every single one of those calls would pay for a full serialize-and-write
+/// of the entire cache map — O(n) work repeated n times.
+const SAVE_THROTTLE: Duration = Duration::from_secs(2);
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct FsEntry {
     name: String,
     path: String,
     size: u64,
+    allocated_size: u64,
     is_dir: bool,
     item_count: u64,
+    symlink: Option<SymlinkInfo>,
+}
+
+/// Present on an `FsEntry` that is a symlink or junction when `follow_links`
+/// is enabled. `destination` is the canonicalized target; `error` is set
+/// instead when the target is missing or the link forms a cycle (the OS
+/// rejects the link chain as a loop, or the link resolves to one of its own
+/// ancestor directories), so broken links are surfaced to the frontend
+/// rather than silently dropped.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SymlinkInfo {
+    destination: Option<String>,
+    error: Option<String>,
+}
+
+/// Maximum number of symlinked-directory hops `calc_size` will follow down
+/// a single branch before giving up, guarding against link cycles that the
+/// OS itself doesn't reject (e.g. a junction whose target contains another
+/// junction pointing back at an ancestor).
+const MAX_LINK_HOPS: usize = 20;
+
+/// Whether a scan should report logical (`meta.len()`) or on-disk
+/// (see `allocated_size_of`) sizes. `allocated_size` is only ever populated when
+/// the mode is `Allocated`, since computing it costs an extra syscall per
+/// file and most callers only care about apparent size.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeMode {
+    #[default]
+    Apparent,
+    Allocated,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -25,37 +75,596 @@ pub struct DriveInfo {
 #[derive(Serialize, Deserialize)]
 pub struct FolderSize {
     size: u64,
+    allocated_size: u64,
+    item_count: u64,
+}
+
+/// Progress event emitted on `scan://progress` while a scan is in flight.
+#[derive(Clone, Serialize)]
+struct ScanProgress {
+    scan_id: u64,
+    entries_checked: u64,
+    bytes_so_far: u64,
+}
+
+/// Tracks the cancel flag for every scan currently running, keyed by the
+/// scan id the frontend generates when it kicks a scan off. `cancel_scan`
+/// looks a scan up here and flips its flag; the traversal notices on its
+/// next loop iteration and unwinds without finishing the subtree.
+#[derive(Default)]
+pub struct ScanRegistry(Mutex<HashMap<u64, Arc<AtomicBool>>>);
+
+impl ScanRegistry {
+    fn register(&self, scan_id: u64) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        self.0.lock().unwrap().insert(scan_id, flag.clone());
+        flag
+    }
+
+    fn unregister(&self, scan_id: u64) {
+        self.0.lock().unwrap().remove(&scan_id);
+    }
+}
+
+/// A directory's aggregated size, keyed by that directory's own mtime at the
+/// time it was computed. Looked up again on the next scan: if the directory's
+/// mtime still matches, the subtree is trusted without being re-walked.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct CachedAggregate {
+    size: u64,
+    allocated_size: u64,
     item_count: u64,
+    mtime_secs: u64,
+}
+
+/// On-disk incremental cache of directory aggregates, keyed by path plus the
+/// `SizeMode`/`follow_links` combination it was computed under — a directory
+/// scanned in `Apparent` mode must not answer a later `Allocated`-mode (or
+/// `follow_links`-toggled) query, since those flags change what the
+/// aggregate actually counts. Backed by a flat bincode file so a subsequent
+/// `get_folder_size` over a mostly unchanged tree can skip straight to
+/// cached subtrees. Cheap to clone — every clone shares the same underlying
+/// map and file path.
+#[derive(Clone, Default)]
+pub struct ScanCache(Arc<ScanCacheInner>);
+
+struct ScanCacheInner {
+    file_path: Mutex<Option<PathBuf>>,
+    entries: Mutex<HashMap<(PathBuf, SizeMode, bool), CachedAggregate>>,
+    last_saved: Mutex<Instant>,
+}
+
+impl Default for ScanCacheInner {
+    fn default() -> Self {
+        Self {
+            file_path: Mutex::new(None),
+            entries: Mutex::new(HashMap::new()),
+            last_saved: Mutex::new(Instant::now() - SAVE_THROTTLE),
+        }
+    }
+}
+
+impl ScanCache {
+    /// Loads a cache previously saved at `file_path`, or starts empty if it
+    /// doesn't exist yet or can't be parsed (e.g. an old/incompatible format).
+    fn load(file_path: PathBuf) -> Self {
+        let entries = fs::read(&file_path)
+            .ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok())
+            .unwrap_or_default();
+        Self(Arc::new(ScanCacheInner {
+            file_path: Mutex::new(Some(file_path)),
+            entries: Mutex::new(entries),
+            last_saved: Mutex::new(Instant::now() - SAVE_THROTTLE),
+        }))
+    }
+
+    /// Returns the cached aggregate for `path` under this exact `mode` /
+    /// `follow_links` combination, only if its mtime still matches — a
+    /// stale entry (directory modified since) is not returned.
+    fn get(
+        &self,
+        path: &Path,
+        mode: SizeMode,
+        follow_links: bool,
+        mtime_secs: u64,
+    ) -> Option<CachedAggregate> {
+        self.0
+            .entries
+            .lock()
+            .unwrap()
+            .get(&(path.to_path_buf(), mode, follow_links))
+            .copied()
+            .filter(|cached| cached.mtime_secs == mtime_secs)
+    }
+
+    fn insert(
+        &self,
+        path: PathBuf,
+        mode: SizeMode,
+        follow_links: bool,
+        aggregate: CachedAggregate,
+    ) {
+        self.0
+            .entries
+            .lock()
+            .unwrap()
+            .insert((path, mode, follow_links), aggregate);
+    }
+
+    /// Persists the current cache contents to disk, throttled like
+    /// `ScanContext::maybe_emit` so a whole-drive scan's flood of concurrent
+    /// per-folder `get_folder_size` calls collapses into at most one actual
+    /// write every `SAVE_THROTTLE`, rather than one full serialize-and-write
+    /// per folder. Holding `last_saved` across the write also means only one
+    /// caller at a time ever writes, and the write itself lands via a
+    /// rename-into-place so a reader never sees a half-written file.
+    /// Best-effort: a skipped or failed write just means the next scan won't
+    /// benefit from this pass.
+    fn save(&self) {
+        let Some(file_path) = self.0.file_path.lock().unwrap().clone() else {
+            return;
+        };
+        let mut last_saved = self.0.last_saved.lock().unwrap();
+        if last_saved.elapsed() < SAVE_THROTTLE {
+            return;
+        }
+        let bytes = {
+            let entries = self.0.entries.lock().unwrap();
+            bincode::serialize(&*entries)
+        };
+        let Ok(bytes) = bytes else {
+            return;
+        };
+        let tmp_path = file_path.with_extension("tmp");
+        if fs::write(&tmp_path, bytes).is_ok() && fs::rename(&tmp_path, &file_path).is_ok() {
+            *last_saved = Instant::now();
+        }
+    }
+
+    fn clear(&self) {
+        self.0.entries.lock().unwrap().clear();
+        if let Some(file_path) = self.0.file_path.lock().unwrap().clone() {
+            let _ = fs::remove_file(file_path);
+        }
+    }
+}
+
+fn mtime_secs(meta: &fs::Metadata) -> Option<u64> {
+    meta.modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// Bundles everything a single scan needs to report itself: which scan it
+/// is, how to reach the frontend, and the flag that tells it to stop. Also
+/// carries the set of file identities already summed, so hardlinked files
+/// met more than once during the one scan only count towards size once.
+struct ScanContext {
+    app: AppHandle,
+    scan_id: u64,
+    cancel: Arc<AtomicBool>,
+    last_emit: Mutex<Instant>,
+    inodes_seen: Mutex<HashSet<(u64, u64)>>,
+    mode: SizeMode,
+    follow_links: bool,
+    cache: ScanCache,
+}
+
+impl ScanContext {
+    fn new(
+        app: AppHandle,
+        scan_id: u64,
+        cancel: Arc<AtomicBool>,
+        mode: SizeMode,
+        follow_links: bool,
+        cache: ScanCache,
+    ) -> Self {
+        Self {
+            app,
+            scan_id,
+            cancel,
+            last_emit: Mutex::new(Instant::now() - PROGRESS_THROTTLE),
+            inodes_seen: Mutex::new(HashSet::new()),
+            mode,
+            follow_links,
+            cache,
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` the first time this file's identity is seen during the
+    /// scan, `false` for every subsequent hardlink to the same content —
+    /// callers should only add the file's size into the total on `true`.
+    /// Files whose identity can't be determined are always counted.
+    fn mark_seen(&self, meta: &fs::Metadata) -> bool {
+        let Some(identity) = file_identity(meta) else {
+            return true;
+        };
+        self.inodes_seen.lock().unwrap().insert(identity)
+    }
+
+    fn maybe_emit(&self, entries_checked: u64, bytes_so_far: u64) {
+        let mut last = self.last_emit.lock().unwrap();
+        if last.elapsed() < PROGRESS_THROTTLE {
+            return;
+        }
+        *last = Instant::now();
+        let _ = self.app.emit(
+            "scan://progress",
+            ScanProgress {
+                scan_id: self.scan_id,
+                entries_checked,
+                bytes_so_far,
+            },
+        );
+    }
+}
+
+/// Identifies a file by volume + file index (Windows) or device + inode
+/// (Unix) so hardlinks to the same content can be recognized as one file.
+/// Returns `None` if the platform can't report the identity for this file.
+#[cfg(windows)]
+fn file_identity(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::windows::fs::MetadataExt;
+    Some((meta.volume_serial_number()? as u64, meta.file_index()?))
+}
+
+#[cfg(unix)]
+fn file_identity(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(any(windows, unix)))]
+fn file_identity(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Whether this file has more than one hardlink pointing at it. A directory
+/// aggregate that summed such a file is order-dependent on `ScanContext`'s
+/// scan-local `inodes_seen` — whether it counted as the "first" occurrence
+/// depends on which other directory the same scan happened to visit first —
+/// so it must never be written to the cross-call `ScanCache`.
+#[cfg(windows)]
+fn has_multiple_links(meta: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    meta.number_of_links().unwrap_or(1) > 1
+}
+
+#[cfg(unix)]
+fn has_multiple_links(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink() > 1
+}
+
+#[cfg(not(any(windows, unix)))]
+fn has_multiple_links(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// On-disk size for a file: on Windows, `GetCompressedFileSizeW`, which
+/// reports the reduced size of NTFS-compressed and sparse files and the plain
+/// file size for other files (not rounded up to whole clusters);
+/// `blocks * 512` elsewhere. Falls back to the apparent size if the platform
+/// call fails.
+#[cfg(windows)]
+fn allocated_size_of(path: &Path, meta: &fs::Metadata) -> u64 {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{GetLastError, NO_ERROR};
+    use windows_sys::Win32::Storage::FileSystem::GetCompressedFileSizeW;
+
+    let wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut high: u32 = 0;
+    let low = unsafe { GetCompressedFileSizeW(wide.as_ptr(), &mut high) };
+    // u32::MAX is ambiguous per the documented contract: it can be a
+    // legitimate low-DWORD value, so only treat it as failure if
+    // GetLastError() actually reports one.
+    if low == u32::MAX && unsafe { GetLastError() } != NO_ERROR {
+        return meta.len();
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+#[cfg(unix)]
+fn allocated_size_of(_path: &Path, meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.blocks() * 512
+}
+
+#[cfg(not(any(windows, unix)))]
+fn allocated_size_of(_path: &Path, meta: &fs::Metadata) -> u64 {
+    meta.len()
+}
+
+/// Resolves a symlink/junction to its canonical target. `error` is set
+/// instead of `destination` when the target is missing, the OS itself
+/// detects a cycle in the link chain, or the target is the link's own
+/// directory or one of its ancestors (following it would cycle).
+fn resolve_symlink(path: &Path) -> SymlinkInfo {
+    match fs::canonicalize(path) {
+        Ok(target) => {
+            let points_at_ancestor = path
+                .parent()
+                .and_then(|parent| fs::canonicalize(parent).ok())
+                .is_some_and(|parent| parent.starts_with(&target));
+            if points_at_ancestor {
+                return SymlinkInfo {
+                    destination: None,
+                    error: Some("link points at one of its own ancestor directories".to_string()),
+                };
+            }
+            SymlinkInfo {
+                destination: Some(strip_extended_prefix(&target)),
+                error: None,
+            }
+        }
+        Err(e) => SymlinkInfo {
+            destination: None,
+            error: Some(e.to_string()),
+        },
+    }
 }
 
 /// Recursively calculates (total_bytes, item_count).
-/// Silently skips entries we can't access. Does not follow symlinks.
-fn calc_size(path: &Path) -> (u64, u64) {
-    let Ok(read_dir) = fs::read_dir(path) else {
-        return (0, 0);
+/// Silently skips entries we can't access. Symlinks and junctions are
+/// skipped unless `ctx.follow_links` is set, in which case they're resolved
+/// and the target is walked/sized as if it were a plain entry — guarded by
+/// `MAX_LINK_HOPS` and `link_chain` so a link cycle can't recurse forever.
+/// Hardlinked files are only added to `size` the first time their identity
+/// is seen in this scan, so shared content isn't double-counted.
+///
+/// Fans directory entries out across rayon's global pool so a single deep
+/// folder saturates all cores instead of one thread; `size`/`item_count`
+/// are accumulated into atomics since subtrees are walked concurrently.
+/// `ctx` carries the cancel flag, the throttled progress emitter, the size
+/// mode, the on-disk cache, and the set of file identities already summed.
+/// Returns `(apparent_size, allocated_size, item_count)`.
+///
+/// Seeds `link_chain` with `path`'s own canonical form before walking, so a
+/// symlink/junction anywhere inside that resolves back to `path` itself is
+/// recognized as a cycle on its very first encounter instead of walking the
+/// whole subtree a second time before the *next* occurrence of the same link
+/// finally matches. Plain subdirectories are not added to the chain, so a
+/// link resolving to some other directory already on the walk (a
+/// subdirectory of `path`, or an ancestor of `path`) is only caught the
+/// second time it is met.
+fn calc_size(path: &Path, ctx: &ScanContext) -> (u64, u64, u64) {
+    let root_chain = root_link_chain(path);
+    let (size, allocated_size, item_count, _had_hardlink) =
+        calc_size_cached(path, ctx, &root_chain);
+    (size, allocated_size, item_count)
+}
+
+/// Canonicalizes `path` into a single-element `link_chain` seed, or an empty
+/// chain if it can't be canonicalized (rare — the directory would have to
+/// vanish between being opened and being scanned).
+fn root_link_chain(path: &Path) -> Vec<PathBuf> {
+    fs::canonicalize(path).map(|c| vec![c]).unwrap_or_default()
+}
+
+/// Looks `path` up in `ctx.cache` first (under the current `mode` /
+/// `follow_links` combination); only re-walks it with `calc_size_into` if
+/// there's no entry or its mtime has changed since. The freshly computed
+/// aggregate is cached afterwards under the directory's current mtime —
+/// unless the scan was cancelled part-way (the totals are then incomplete),
+/// or the subtree contained a hardlinked file, in which case the result
+/// is scan-order-dependent (see `has_multiple_links`) and must not be
+/// reused by a later, independent scan. The returned `bool` reports whether
+/// a hardlink was found, so an ancestor directory's own aggregate is also
+/// left out of the cache.
+fn calc_size_cached(
+    path: &Path,
+    ctx: &ScanContext,
+    link_chain: &[PathBuf],
+) -> (u64, u64, u64, bool) {
+    let mtime = fs::metadata(path).ok().as_ref().and_then(mtime_secs);
+    if let Some(mtime) = mtime {
+        if let Some(cached) = ctx.cache.get(path, ctx.mode, ctx.follow_links, mtime) {
+            return (cached.size, cached.allocated_size, cached.item_count, false);
+        }
+    }
+
+    let total_size = AtomicU64::new(0);
+    let total_allocated = AtomicU64::new(0);
+    let total_count = AtomicU64::new(0);
+    let had_hardlink = AtomicBool::new(false);
+    calc_size_into(
+        path,
+        &total_size,
+        &total_allocated,
+        &total_count,
+        &had_hardlink,
+        ctx,
+        link_chain,
+    );
+    let size = total_size.load(Ordering::Relaxed);
+    let allocated_size = total_allocated.load(Ordering::Relaxed);
+    let item_count = total_count.load(Ordering::Relaxed);
+    let had_hardlink = had_hardlink.load(Ordering::Relaxed);
+
+    if let Some(mtime) = mtime {
+        if !had_hardlink && !ctx.is_cancelled() {
+            ctx.cache.insert(
+                path.to_path_buf(),
+                ctx.mode,
+                ctx.follow_links,
+                CachedAggregate {
+                    size,
+                    allocated_size,
+                    item_count,
+                    mtime_secs: mtime,
+                },
+            );
+        }
+    }
+    (size, allocated_size, item_count, had_hardlink)
+}
+
+fn calc_size_into(
+    path: &Path,
+    total_size: &AtomicU64,
+    total_allocated: &AtomicU64,
+    total_count: &AtomicU64,
+    had_hardlink: &AtomicBool,
+    ctx: &ScanContext,
+    link_chain: &[PathBuf],
+) {
+    if ctx.is_cancelled() {
+        return;
+    }
+    let Ok(read_dir) = read_dir_extended(path) else {
+        return;
     };
-    let mut total_size = 0u64;
-    let mut total_count = 0u64;
-    for entry in read_dir.flatten() {
+    let entries: Vec<_> = read_dir.flatten().collect();
+    entries.par_iter().for_each(|entry| {
+        if ctx.is_cancelled() {
+            return;
+        }
         let entry_path = entry.path();
         let Ok(meta) = entry_path.symlink_metadata() else {
-            continue;
+            return;
         };
-        total_count += 1;
+        total_count.fetch_add(1, Ordering::Relaxed);
         if meta.is_dir() {
-            let (s, c) = calc_size(&entry_path);
-            total_size += s;
-            total_count += c;
+            let (size, allocated, count, child_had_hardlink) =
+                calc_size_cached(&entry_path, ctx, link_chain);
+            total_size.fetch_add(size, Ordering::Relaxed);
+            total_allocated.fetch_add(allocated, Ordering::Relaxed);
+            total_count.fetch_add(count, Ordering::Relaxed);
+            if child_had_hardlink {
+                had_hardlink.store(true, Ordering::Relaxed);
+            }
         } else if meta.is_file() {
-            total_size += meta.len();
+            if has_multiple_links(&meta) {
+                had_hardlink.store(true, Ordering::Relaxed);
+            }
+            if ctx.mark_seen(&meta) {
+                total_size.fetch_add(meta.len(), Ordering::Relaxed);
+                if ctx.mode == SizeMode::Allocated {
+                    total_allocated
+                        .fetch_add(allocated_size_of(&entry_path, &meta), Ordering::Relaxed);
+                }
+            }
+        } else if ctx.follow_links && meta.file_type().is_symlink() {
+            follow_symlink_into(
+                &entry_path,
+                total_size,
+                total_allocated,
+                total_count,
+                had_hardlink,
+                ctx,
+                link_chain,
+            );
         }
+        ctx.maybe_emit(
+            total_count.load(Ordering::Relaxed),
+            total_size.load(Ordering::Relaxed),
+        );
+    });
+}
+
+/// Resolves a symlink reached while `follow_links` is on and walks its
+/// target. Directory targets extend `link_chain`; if the canonical target
+/// is already in the chain (a cycle) or the chain has grown past
+/// `MAX_LINK_HOPS`, the branch is abandoned rather than followed further.
+fn follow_symlink_into(
+    link_path: &Path,
+    total_size: &AtomicU64,
+    total_allocated: &AtomicU64,
+    total_count: &AtomicU64,
+    had_hardlink: &AtomicBool,
+    ctx: &ScanContext,
+    link_chain: &[PathBuf],
+) {
+    let Ok(canonical) = fs::canonicalize(link_path) else {
+        return; // broken target
+    };
+    if link_chain.len() >= MAX_LINK_HOPS || link_chain.contains(&canonical) {
+        return; // cycle, or too many hops to be worth following further
     }
-    (total_size, total_count)
+    let Ok(target_meta) = fs::metadata(&canonical) else {
+        return;
+    };
+    if target_meta.is_dir() {
+        let mut chain = link_chain.to_vec();
+        chain.push(canonical.clone());
+        let (size, allocated, count, child_had_hardlink) =
+            calc_size_cached(&canonical, ctx, &chain);
+        total_size.fetch_add(size, Ordering::Relaxed);
+        total_allocated.fetch_add(allocated, Ordering::Relaxed);
+        total_count.fetch_add(count, Ordering::Relaxed);
+        if child_had_hardlink {
+            had_hardlink.store(true, Ordering::Relaxed);
+        }
+    } else if target_meta.is_file() {
+        if has_multiple_links(&target_meta) {
+            had_hardlink.store(true, Ordering::Relaxed);
+        }
+        if ctx.mark_seen(&target_meta) {
+            total_size.fetch_add(target_meta.len(), Ordering::Relaxed);
+            if ctx.mode == SizeMode::Allocated {
+                total_allocated.fetch_add(
+                    allocated_size_of(&canonical, &target_meta),
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+}
+
+/// Prefixes an absolute Windows path with `\\?\` (or `\\?\UNC\` for a UNC
+/// path) so it can be opened past the 260-character MAX_PATH limit, which
+/// `fs::read_dir` otherwise hits silently on deep trees (node_modules-style
+/// nesting is a common culprit). No-op on platforms without that limit.
+#[cfg(windows)]
+fn extended_length_path(path: &Path) -> PathBuf {
+    let raw = path.as_os_str().to_string_lossy();
+    if raw.starts_with(r"\\?\") {
+        return path.to_path_buf();
+    }
+    if let Some(unc) = raw.strip_prefix(r"\\") {
+        return PathBuf::from(format!(r"\\?\UNC\{unc}"));
+    }
+    PathBuf::from(format!(r"\\?\{raw}"))
+}
+
+#[cfg(not(windows))]
+fn extended_length_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Inverse of `extended_length_path`: strips the `\\?\`/`\\?\UNC\` prefix so
+/// paths we report back to the frontend look like ordinary Windows paths.
+fn strip_extended_prefix(path: &Path) -> String {
+    let raw = path.to_string_lossy();
+    if let Some(rest) = raw.strip_prefix(r"\\?\UNC\") {
+        return format!(r"\\{rest}");
+    }
+    if let Some(rest) = raw.strip_prefix(r"\\?\") {
+        return rest.to_string();
+    }
+    raw.to_string()
+}
+
+/// Like `fs::read_dir`, but transparently uses the Windows extended-length
+/// form so directories nested past MAX_PATH aren't silently skipped.
+fn read_dir_extended(path: &Path) -> std::io::Result<fs::ReadDir> {
+    fs::read_dir(extended_length_path(path))
 }
 
 /// Returns the number of direct children without recursing.
 fn direct_child_count(path: &Path) -> u64 {
-    fs::read_dir(path)
+    read_dir_extended(path)
         .map(|rd| rd.count() as u64)
         .unwrap_or(0)
 }
@@ -83,10 +692,19 @@ fn get_drives() -> Vec<DriveInfo> {
 /// PHASE 1 — Fast, non-recursive scan. Returns entries almost instantly.
 /// Files get their real size. Folders get size=0 and direct child count only.
 /// The frontend then requests folder sizes individually via get_folder_size.
+/// `mode` selects whether file `allocated_size` is computed at all: it costs
+/// an extra syscall per file, so it's left at 0 unless `SizeMode::Allocated`.
+/// `follow_links` is opt-in: when unset, symlinks/junctions are skipped as
+/// before; when set, they're resolved and reported with a `symlink` field
+/// describing either the canonical destination or why it couldn't be resolved.
 #[tauri::command]
-fn scan_directory_fast(path: String) -> Result<Vec<FsEntry>, String> {
+fn scan_directory_fast(
+    path: String,
+    mode: SizeMode,
+    follow_links: bool,
+) -> Result<Vec<FsEntry>, String> {
     let dir = Path::new(&path);
-    let read_dir = fs::read_dir(dir).map_err(|e| format!("{e}"))?;
+    let read_dir = read_dir_extended(dir).map_err(|e| format!("{e}"))?;
 
     let mut entries: Vec<FsEntry> = read_dir
         .flatten()
@@ -96,54 +714,484 @@ fn scan_directory_fast(path: String) -> Result<Vec<FsEntry>, String> {
             let Ok(meta) = entry_path.symlink_metadata() else {
                 return None;
             };
-            let is_dir = meta.is_dir();
-            let (size, item_count) = if is_dir {
-                (0, direct_child_count(&entry_path))
+
+            let allocated_of = |p: &Path, m: &fs::Metadata| {
+                if mode == SizeMode::Allocated {
+                    allocated_size_of(p, m)
+                } else {
+                    0
+                }
+            };
+
+            let (is_dir, size, allocated_size, item_count, symlink) = if meta.is_dir() {
+                (true, 0, 0, direct_child_count(&entry_path), None)
             } else if meta.is_file() {
-                (meta.len(), 0)
+                (false, meta.len(), allocated_of(&entry_path, &meta), 0, None)
+            } else if meta.file_type().is_symlink() && follow_links {
+                let info = resolve_symlink(&entry_path);
+                match fs::metadata(&entry_path) {
+                    Ok(target_meta) if target_meta.is_dir() => {
+                        (true, 0, 0, direct_child_count(&entry_path), Some(info))
+                    }
+                    Ok(target_meta) => (
+                        false,
+                        target_meta.len(),
+                        allocated_of(&entry_path, &target_meta),
+                        0,
+                        Some(info),
+                    ),
+                    Err(_) => (false, 0, 0, 0, Some(info)),
+                }
             } else {
                 return None; // skip symlinks / junctions
             };
+
             Some(FsEntry {
                 name,
-                path: entry_path.to_string_lossy().to_string(),
+                path: strip_extended_prefix(&entry_path),
                 size,
+                allocated_size,
                 is_dir,
                 item_count,
+                symlink,
             })
         })
         .collect();
 
-    // Stable initial order: folders first, then alphabetical
+    sort_entries(&mut entries);
+    Ok(entries)
+}
+
+/// Stable initial order: folders first, then alphabetical.
+fn sort_entries(entries: &mut [FsEntry]) {
     entries.sort_by(|a, b| {
         b.is_dir
             .cmp(&a.is_dir)
             .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
     });
-    Ok(entries)
 }
 
 /// PHASE 2 — Recursive size for a single folder. Called concurrently per folder
 /// from the frontend. Runs on a blocking thread so it never freezes the UI.
+/// `scan_id` identifies this call for progress events and `cancel_scan`.
+/// Subtrees whose mtime matches `cache` are trusted without being re-walked.
+#[tauri::command]
+async fn get_folder_size(
+    path: String,
+    scan_id: u64,
+    mode: SizeMode,
+    follow_links: bool,
+    app: AppHandle,
+    registry: State<'_, ScanRegistry>,
+    cache: State<'_, ScanCache>,
+) -> Result<FolderSize, String> {
+    let cancel = registry.register(scan_id);
+    let cache = cache.inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let ctx = ScanContext::new(app, scan_id, cancel, mode, follow_links, cache.clone());
+        let (size, allocated_size, item_count) = calc_size(Path::new(&path), &ctx);
+        cache.save();
+        FolderSize {
+            size,
+            allocated_size,
+            item_count,
+        }
+    })
+    .await
+    .map_err(|e| e.to_string());
+    registry.unregister(scan_id);
+    result
+}
+
+/// Full recursive scan of a directory: like `scan_directory_fast`, but each
+/// child folder's size is computed up front via `calc_size` instead of being
+/// left at 0 for the frontend to request lazily. Used for whole-drive scans
+/// where the caller wants one deep pass with progress/cancel support rather
+/// than many small `get_folder_size` calls.
+///
+/// Every direct child is sized against `root_chain` (the scan root's own
+/// canonical form) rather than a fresh empty chain per child — otherwise a
+/// symlink found anywhere under one child that resolves back to `dir` itself
+/// would go undetected as a cycle, since a per-child `calc_size` only knows
+/// to guard against links back to that one child, not to the scan root.
+#[tauri::command]
+async fn scan_directory_recursive(
+    path: String,
+    scan_id: u64,
+    mode: SizeMode,
+    follow_links: bool,
+    app: AppHandle,
+    registry: State<'_, ScanRegistry>,
+    cache: State<'_, ScanCache>,
+) -> Result<Vec<FsEntry>, String> {
+    let cancel = registry.register(scan_id);
+    let cache = cache.inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let ctx = ScanContext::new(app, scan_id, cancel, mode, follow_links, cache.clone());
+        let dir = Path::new(&path);
+        let root_chain = root_link_chain(dir);
+        let read_dir = read_dir_extended(dir).map_err(|e| format!("{e}"))?;
+
+        let mut entries: Vec<FsEntry> = read_dir
+            .flatten()
+            .filter_map(|entry| {
+                let entry_path = entry.path();
+                let name = entry.file_name().to_string_lossy().to_string();
+                let Ok(meta) = entry_path.symlink_metadata() else {
+                    return None;
+                };
+
+                let allocated_of = |p: &Path, m: &fs::Metadata| {
+                    if mode == SizeMode::Allocated {
+                        allocated_size_of(p, m)
+                    } else {
+                        0
+                    }
+                };
+
+                let (is_dir, size, allocated_size, item_count, symlink) = if meta.is_dir() {
+                    let (size, allocated_size, item_count, _) =
+                        calc_size_cached(&entry_path, &ctx, &root_chain);
+                    (true, size, allocated_size, item_count, None)
+                } else if meta.is_file() {
+                    (false, meta.len(), allocated_of(&entry_path, &meta), 0, None)
+                } else if meta.file_type().is_symlink() && follow_links {
+                    let info = resolve_symlink(&entry_path);
+                    match fs::metadata(&entry_path) {
+                        Ok(target_meta) if target_meta.is_dir() => {
+                            let total_size = AtomicU64::new(0);
+                            let total_allocated = AtomicU64::new(0);
+                            let total_count = AtomicU64::new(0);
+                            let had_hardlink = AtomicBool::new(false);
+                            follow_symlink_into(
+                                &entry_path,
+                                &total_size,
+                                &total_allocated,
+                                &total_count,
+                                &had_hardlink,
+                                &ctx,
+                                &root_chain,
+                            );
+                            (
+                                true,
+                                total_size.load(Ordering::Relaxed),
+                                total_allocated.load(Ordering::Relaxed),
+                                total_count.load(Ordering::Relaxed),
+                                Some(info),
+                            )
+                        }
+                        Ok(target_meta) => (
+                            false,
+                            target_meta.len(),
+                            allocated_of(&entry_path, &target_meta),
+                            0,
+                            Some(info),
+                        ),
+                        Err(_) => (false, 0, 0, 0, Some(info)),
+                    }
+                } else {
+                    return None; // skip symlinks / junctions
+                };
+
+                Some(FsEntry {
+                    name,
+                    path: strip_extended_prefix(&entry_path),
+                    size,
+                    allocated_size,
+                    is_dir,
+                    item_count,
+                    symlink,
+                })
+            })
+            .collect();
+
+        sort_entries(&mut entries);
+        cache.save();
+        Ok(entries)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    registry.unregister(scan_id);
+    result
+}
+
+/// Flips the cancel flag for `scan_id` if it's still running. The traversal
+/// notices on its next loop iteration; this returns immediately either way.
+#[tauri::command]
+fn cancel_scan(scan_id: u64, registry: State<'_, ScanRegistry>) {
+    if let Some(flag) = registry.0.lock().unwrap().get(&scan_id) {
+        flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Drops every cached directory aggregate, in memory and on disk, so the
+/// next scan re-walks everything from scratch.
+#[tauri::command]
+fn clear_cache(cache: State<'_, ScanCache>) {
+    cache.clear();
+}
+
+/// A set of files with identical content. `paths.len() - 1` of them are
+/// redundant — `size * (paths.len() - 1)` bytes could be reclaimed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DuplicateGroup {
+    size: u64,
+    paths: Vec<String>,
+}
+
+/// How much of a file to read for the cheap prefix hash in stage 2 of
+/// `find_duplicates`, before committing to a full read in stage 3.
+const PARTIAL_HASH_PREFIX: usize = 8 * 1024;
+
+/// Collects every regular file under `path`, keeping only the first path seen
+/// for each hardlink identity (via `ctx.mark_seen`) — two hardlinks share one
+/// on-disk data, so treating both as separate "duplicate" copies would
+/// overstate how much space `find_duplicates` could actually reclaim.
+fn collect_files(path: &Path, ctx: &ScanContext) -> Vec<(PathBuf, u64)> {
+    let results = Mutex::new(Vec::new());
+    collect_files_into(path, &results, ctx);
+    results.into_inner().unwrap()
+}
+
+fn collect_files_into(path: &Path, results: &Mutex<Vec<(PathBuf, u64)>>, ctx: &ScanContext) {
+    if ctx.is_cancelled() {
+        return;
+    }
+    let Ok(read_dir) = read_dir_extended(path) else {
+        return;
+    };
+    let entries: Vec<_> = read_dir.flatten().collect();
+    entries.par_iter().for_each(|entry| {
+        if ctx.is_cancelled() {
+            return;
+        }
+        let entry_path = entry.path();
+        let Ok(meta) = entry_path.symlink_metadata() else {
+            return;
+        };
+        if meta.is_dir() {
+            collect_files_into(&entry_path, results, ctx);
+        } else if meta.is_file() && ctx.mark_seen(&meta) {
+            let mut results = results.lock().unwrap();
+            results.push((entry_path, meta.len()));
+            ctx.maybe_emit(results.len() as u64, 0);
+        }
+    });
+}
+
+/// Hashes the first `PARTIAL_HASH_PREFIX` bytes of a file, used to cheaply
+/// eliminate same-size files that clearly differ before paying for a full read.
+fn hash_prefix(path: &Path) -> Option<[u8; 32]> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut buf = [0u8; PARTIAL_HASH_PREFIX];
+    let n = file.read(&mut buf).ok()?;
+    Some(*blake3::hash(&buf[..n]).as_bytes())
+}
+
+/// Hashes a whole file to confirm equality once size and prefix both match.
+fn hash_full(path: &Path) -> Option<[u8; 32]> {
+    let bytes = fs::read(path).ok()?;
+    Some(*blake3::hash(&bytes).as_bytes())
+}
+
+/// Finds groups of byte-identical files under `path`, using czkawka's staged
+/// approach: bucket by exact size (a unique size can never collide), then by
+/// a cheap hash of the first 8 KiB to drop obvious non-matches, and only then
+/// hash the full contents of whatever's left to confirm equality. Groups are
+/// sorted by wasted space (`size * (paths.len() - 1)`) so the biggest wins
+/// come first. Runs on a blocking thread and shares the cancel/progress
+/// machinery with `get_folder_size`.
 #[tauri::command]
-async fn get_folder_size(path: String) -> Result<FolderSize, String> {
-    tokio::task::spawn_blocking(move || {
-        let (size, item_count) = calc_size(Path::new(&path));
-        Ok(FolderSize { size, item_count })
+async fn find_duplicates(
+    path: String,
+    scan_id: u64,
+    app: AppHandle,
+    registry: State<'_, ScanRegistry>,
+) -> Result<Vec<DuplicateGroup>, String> {
+    let cancel = registry.register(scan_id);
+    let result = tokio::task::spawn_blocking(move || {
+        let ctx = ScanContext::new(
+            app,
+            scan_id,
+            cancel,
+            SizeMode::Apparent,
+            false,
+            ScanCache::default(),
+        );
+        let files = collect_files(Path::new(&path), &ctx);
+
+        let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        for (file_path, size) in files {
+            by_size.entry(size).or_default().push(file_path);
+        }
+
+        let mut groups: Vec<DuplicateGroup> = by_size
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .par_bridge()
+            .filter_map(|(size, paths)| {
+                if ctx.is_cancelled() {
+                    return None;
+                }
+
+                let mut by_prefix: HashMap<[u8; 32], Vec<PathBuf>> = HashMap::new();
+                for file_path in paths {
+                    if let Some(hash) = hash_prefix(&file_path) {
+                        by_prefix.entry(hash).or_default().push(file_path);
+                    }
+                }
+
+                let mut by_full: HashMap<[u8; 32], Vec<String>> = HashMap::new();
+                for candidates in by_prefix.into_values().filter(|c| c.len() > 1) {
+                    for file_path in candidates {
+                        if let Some(hash) = hash_full(&file_path) {
+                            by_full
+                                .entry(hash)
+                                .or_default()
+                                .push(strip_extended_prefix(&file_path));
+                        }
+                    }
+                }
+
+                Some(
+                    by_full
+                        .into_values()
+                        .filter(|paths| paths.len() > 1)
+                        .map(|paths| DuplicateGroup { size, paths })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .flatten()
+            .collect();
+
+        groups.sort_by_key(|g| std::cmp::Reverse(g.size * (g.paths.len() as u64 - 1)));
+        Ok(groups)
     })
     .await
-    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    registry.unregister(scan_id);
+    result
+}
+
+/// Where the incremental scan cache lives on disk: the app's cache dir if
+/// available, falling back to the system temp dir.
+fn cache_file_path(app: &AppHandle) -> PathBuf {
+    app.path()
+        .app_cache_dir()
+        .unwrap_or_else(|_| std::env::temp_dir())
+        .join("scan-cache.bin")
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .manage(ScanRegistry::default())
+        .setup(|app| {
+            let cache_path = cache_file_path(&app.handle());
+            if let Some(parent) = cache_path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            app.manage(ScanCache::load(cache_path));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             get_drives,
             scan_directory_fast,
-            get_folder_size
+            get_folder_size,
+            scan_directory_recursive,
+            cancel_scan,
+            find_duplicates,
+            clear_cache
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    /// A directory under the system temp dir that removes itself on drop, so
+    /// a fixture doesn't need manual cleanup at every assertion/return point.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(label: &str) -> Self {
+            static COUNTER: AtomicU32 = AtomicU32::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let dir = std::env::temp_dir().join(format!(
+                "windows-storage-test-{label}-{}-{n}",
+                std::process::id()
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[cfg(unix)]
+    fn symlink_dir(target: &Path, link: &Path) {
+        std::os::unix::fs::symlink(target, link).unwrap();
+    }
+
+    #[cfg(windows)]
+    fn symlink_dir(target: &Path, link: &Path) {
+        std::os::windows::fs::symlink_dir(target, link).unwrap();
+    }
+
+    /// Regression test for the chunk0-6 cycle bug: a symlink directly inside
+    /// the scan root that points back at the root itself must be caught as a
+    /// cycle the first time it's followed, not just the second — so
+    /// `root_link_chain` has to seed the chain with the root's own canonical
+    /// form up front.
+    #[test]
+    #[cfg(any(unix, windows))]
+    fn root_link_chain_catches_self_referencing_symlink() {
+        let root = TempDir::new("cycle-root");
+        symlink_dir(root.path(), &root.path().join("self"));
+
+        let canonical_root = fs::canonicalize(root.path()).unwrap();
+        let chain = root_link_chain(root.path());
+        assert_eq!(chain, vec![canonical_root.clone()]);
+
+        // Following "self" resolves back to the root's own canonical path —
+        // exactly what `chain` must already contain for the cycle guard in
+        // `follow_symlink_into` to abort on this first encounter.
+        let canonical_link_target = fs::canonicalize(root.path().join("self")).unwrap();
+        assert_eq!(canonical_link_target, canonical_root);
+        assert!(chain.contains(&canonical_link_target));
+    }
+
+    /// Regression test for the chunk0-7 cache-invalidation bug: a cached
+    /// aggregate must not answer a lookup made under a different
+    /// `SizeMode`/`follow_links` combination, or once the mtime it was
+    /// cached under no longer matches.
+    #[test]
+    fn cache_is_keyed_by_mode_follow_links_and_mtime() {
+        let cache = ScanCache::default();
+        let path = PathBuf::from("/some/dir");
+        let aggregate = CachedAggregate {
+            size: 123,
+            allocated_size: 64,
+            item_count: 4,
+            mtime_secs: 1000,
+        };
+        cache.insert(path.clone(), SizeMode::Apparent, false, aggregate);
+
+        assert!(cache.get(&path, SizeMode::Apparent, false, 1000).is_some());
+        assert!(cache.get(&path, SizeMode::Allocated, false, 1000).is_none());
+        assert!(cache.get(&path, SizeMode::Apparent, true, 1000).is_none());
+        assert!(cache.get(&path, SizeMode::Apparent, false, 1001).is_none());
+    }
+}